@@ -30,7 +30,7 @@ use derive::{
 };
 use indexmap::IndexMap;
 
-use crate::{TrKey, Wpkh};
+use crate::{TrKey, TrMusig, TrTree, Wpkh, WshSortedMulti};
 
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
 #[display(lowercase)]
@@ -77,6 +77,36 @@ pub trait Descriptor<K = XpubDerivable, V = ()>: DeriveScripts {
 
     fn compr_keyset(&self, terminal: Terminal) -> IndexMap<CompressedPk, KeyOrigin>;
     fn xonly_keyset(&self, terminal: Terminal) -> IndexMap<XOnlyPk, TapDerivation>;
+
+    /// Computes the `PSBT_IN_BIP32_DERIVATION` / `PSBT_OUT_BIP32_DERIVATION` map for a legacy
+    /// or segwit v0 input or output controlled by this descriptor at the given `terminal`.
+    ///
+    /// The returned map is meant to be merged into the corresponding PSBT field so a cold
+    /// signer can recognize which of its keys are used by the transaction without having to
+    /// derive and compare public keys itself.
+    fn psbt_bip32_derivation(&self, terminal: Terminal) -> IndexMap<CompressedPk, KeyOrigin> {
+        self.compr_keyset(terminal)
+    }
+
+    /// Computes the taproot internal key and the `PSBT_IN_TAP_BIP32_DERIVATION` /
+    /// `PSBT_OUT_TAP_BIP32_DERIVATION` map for a taproot input or output controlled by this
+    /// descriptor at the given `terminal`.
+    ///
+    /// The internal key is the derived xonly key which does not participate in any tapscript
+    /// leaf (i.e. has an empty set of leaf hashes); for key-path-only descriptors this is the
+    /// only key in the set. Both values are meant to be merged into the corresponding PSBT
+    /// `tap_internal_key` field and `tap_bip32_derivation` map.
+    fn psbt_tap_derivation(
+        &self,
+        terminal: Terminal,
+    ) -> (Option<XOnlyPk>, IndexMap<XOnlyPk, TapDerivation>) {
+        let keyset = self.xonly_keyset(terminal);
+        let internal_key = keyset
+            .iter()
+            .find(|(_, derivation)| derivation.leaf_hashes.is_empty())
+            .map(|(xonly, _)| *xonly);
+        (internal_key, keyset)
+    }
 }
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug, From)]
@@ -119,10 +149,10 @@ pub enum StdDescr<S: DeriveSet = XpubDerivable> {
     /*
     #[from]
     WshMulti(WshMulti<S::Compr>),
-
+     */
     #[from]
     WshSortedMulti(WshSortedMulti<S::Compr>),
-
+    /*
     #[from]
     WshTlMulti(WshTlMulti<S::Compr>),
 
@@ -131,19 +161,18 @@ pub enum StdDescr<S: DeriveSet = XpubDerivable> {
      */
     #[from]
     TrKey(TrKey<S::XOnly>),
-    /*
     #[from]
     TrMusig(TrMusig<S::XOnly>),
-
+    /*
     #[from]
     TrMulti(TrMulti<S::XOnly>),
 
     #[from]
     TrTlMulti(TrTlMulti<S::XOnly>),
-
+     */
     #[from]
     TrTree(TrTree<S::XOnly>),
-
+    /*
     // This should go into LNP:
     Bolt(Bolt<S::Compr>)
 
@@ -169,14 +198,20 @@ impl<S: DeriveSet> Derive<DerivedScript> for StdDescr<S> {
     fn default_keychain(&self) -> Keychain {
         match self {
             StdDescr::Wpkh(d) => d.default_keychain(),
+            StdDescr::WshSortedMulti(d) => d.default_keychain(),
             StdDescr::TrKey(d) => d.default_keychain(),
+            StdDescr::TrMusig(d) => d.default_keychain(),
+            StdDescr::TrTree(d) => d.default_keychain(),
         }
     }
 
     fn keychains(&self) -> BTreeSet<Keychain> {
         match self {
             StdDescr::Wpkh(d) => d.keychains(),
+            StdDescr::WshSortedMulti(d) => d.keychains(),
             StdDescr::TrKey(d) => d.keychains(),
+            StdDescr::TrMusig(d) => d.keychains(),
+            StdDescr::TrTree(d) => d.keychains(),
         }
     }
 
@@ -187,7 +222,10 @@ impl<S: DeriveSet> Derive<DerivedScript> for StdDescr<S> {
     ) -> DerivedScript {
         match self {
             StdDescr::Wpkh(d) => d.derive(keychain, index),
+            StdDescr::WshSortedMulti(d) => d.derive(keychain, index),
             StdDescr::TrKey(d) => d.derive(keychain, index),
+            StdDescr::TrMusig(d) => d.derive(keychain, index),
+            StdDescr::TrTree(d) => d.derive(keychain, index),
         }
     }
 }
@@ -202,14 +240,20 @@ where Self: Derive<DerivedScript>
     fn class(&self) -> SpkClass {
         match self {
             StdDescr::Wpkh(d) => d.class(),
+            StdDescr::WshSortedMulti(d) => d.class(),
             StdDescr::TrKey(d) => d.class(),
+            StdDescr::TrMusig(d) => d.class(),
+            StdDescr::TrTree(d) => d.class(),
         }
     }
 
     fn keys(&self) -> Self::KeyIter<'_> {
         match self {
             StdDescr::Wpkh(d) => d.keys().collect::<Vec<_>>(),
+            StdDescr::WshSortedMulti(d) => d.keys().collect::<Vec<_>>(),
             StdDescr::TrKey(d) => d.keys().collect::<Vec<_>>(),
+            StdDescr::TrMusig(d) => d.keys().collect::<Vec<_>>(),
+            StdDescr::TrTree(d) => d.keys().collect::<Vec<_>>(),
         }
         .into_iter()
     }
@@ -219,7 +263,10 @@ where Self: Derive<DerivedScript>
     fn xpubs(&self) -> Self::XpubIter<'_> {
         match self {
             StdDescr::Wpkh(d) => d.xpubs().collect::<Vec<_>>(),
+            StdDescr::WshSortedMulti(d) => d.xpubs().collect::<Vec<_>>(),
             StdDescr::TrKey(d) => d.xpubs().collect::<Vec<_>>(),
+            StdDescr::TrMusig(d) => d.xpubs().collect::<Vec<_>>(),
+            StdDescr::TrTree(d) => d.xpubs().collect::<Vec<_>>(),
         }
         .into_iter()
     }
@@ -227,14 +274,48 @@ where Self: Derive<DerivedScript>
     fn compr_keyset(&self, terminal: Terminal) -> IndexMap<CompressedPk, KeyOrigin> {
         match self {
             StdDescr::Wpkh(d) => d.compr_keyset(terminal),
+            StdDescr::WshSortedMulti(d) => d.compr_keyset(terminal),
             StdDescr::TrKey(d) => d.compr_keyset(terminal),
+            StdDescr::TrMusig(d) => d.compr_keyset(terminal),
+            StdDescr::TrTree(d) => d.compr_keyset(terminal),
         }
     }
 
     fn xonly_keyset(&self, terminal: Terminal) -> IndexMap<XOnlyPk, TapDerivation> {
         match self {
             StdDescr::Wpkh(d) => d.xonly_keyset(terminal),
+            StdDescr::WshSortedMulti(d) => d.xonly_keyset(terminal),
             StdDescr::TrKey(d) => d.xonly_keyset(terminal),
+            StdDescr::TrMusig(d) => d.xonly_keyset(terminal),
+            StdDescr::TrTree(d) => d.xonly_keyset(terminal),
+        }
+    }
+
+    // `psbt_bip32_derivation` and `psbt_tap_derivation` have trait defaults, but those
+    // defaults resolve against `Self = StdDescr<K>` and so never see a variant's own
+    // override (e.g. `TrMusig`'s). Forward explicitly, same as every other method above, so
+    // a variant-specific override is actually reachable through `StdDescr`.
+
+    fn psbt_bip32_derivation(&self, terminal: Terminal) -> IndexMap<CompressedPk, KeyOrigin> {
+        match self {
+            StdDescr::Wpkh(d) => d.psbt_bip32_derivation(terminal),
+            StdDescr::WshSortedMulti(d) => d.psbt_bip32_derivation(terminal),
+            StdDescr::TrKey(d) => d.psbt_bip32_derivation(terminal),
+            StdDescr::TrMusig(d) => d.psbt_bip32_derivation(terminal),
+            StdDescr::TrTree(d) => d.psbt_bip32_derivation(terminal),
+        }
+    }
+
+    fn psbt_tap_derivation(
+        &self,
+        terminal: Terminal,
+    ) -> (Option<XOnlyPk>, IndexMap<XOnlyPk, TapDerivation>) {
+        match self {
+            StdDescr::Wpkh(d) => d.psbt_tap_derivation(terminal),
+            StdDescr::WshSortedMulti(d) => d.psbt_tap_derivation(terminal),
+            StdDescr::TrKey(d) => d.psbt_tap_derivation(terminal),
+            StdDescr::TrMusig(d) => d.psbt_tap_derivation(terminal),
+            StdDescr::TrTree(d) => d.psbt_tap_derivation(terminal),
         }
     }
 }
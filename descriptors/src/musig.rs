@@ -0,0 +1,242 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2023 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2023 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2023 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+use std::{iter, vec};
+
+use bp::ScriptPubkey;
+use derive::{
+    Derive, DeriveXOnly, DerivedScript, KeyOrigin, Keychain, NormalIndex, TapDerivation, Terminal,
+    XOnlyPk, XpubSpec,
+};
+use indexmap::IndexMap;
+use secp256k1::{Parity, PublicKey, Scalar, SECP256K1};
+
+use crate::taghash::tagged_hash;
+use crate::{CompressedPk, Descriptor, SpkClass};
+
+/// A taproot output controlled, key-path only, by the MuSig2 aggregate of several
+/// participants' keys, so the resulting output is indistinguishable on-chain from a plain
+/// `TrKey` single-sig output.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase", bound(
+        serialize = "K: serde::Serialize",
+        deserialize = "K: serde::Deserialize<'de>"
+    ))
+)]
+pub struct TrMusig<K: DeriveXOnly> {
+    participants: Vec<K>,
+}
+
+impl<K: DeriveXOnly> TrMusig<K> {
+    /// Creates a MuSig2 taproot descriptor out of the participants' keys.
+    ///
+    /// # Panics
+    ///
+    /// If fewer than two participants are provided.
+    pub fn new(participants: impl IntoIterator<Item = K>) -> Self {
+        let participants = participants.into_iter().collect::<Vec<_>>();
+        assert!(participants.len() >= 2, "TrMusig requires at least two participants");
+        TrMusig { participants }
+    }
+
+    fn aggregate_key(&self, keychain: Keychain, index: NormalIndex) -> (XOnlyPk, Parity) {
+        let keys = self.participants.iter().map(|key| key.derive(keychain, index)).collect::<Vec<_>>();
+
+        let mut sorted = keys.clone();
+        sorted.sort();
+        let list_hash = keyagg_list_hash(&sorted);
+        let second_distinct = sorted.iter().find(|key| **key != sorted[0]).copied();
+
+        let mut acc: Option<PublicKey> = None;
+        for key in &keys {
+            let coeff = if Some(*key) == second_distinct {
+                scalar_one()
+            } else {
+                keyagg_coefficient(list_hash, *key)
+            };
+            let point = key.public_key(Parity::Even).mul_tweak(SECP256K1, &coeff)
+                .expect("coefficient is a valid non-zero scalar");
+            acc = Some(match acc {
+                None => point,
+                Some(acc) => acc.combine(&point).expect("aggregate key is the point at infinity"),
+            });
+        }
+        acc.expect("TrMusig always has at least two participants").x_only_public_key()
+    }
+}
+
+impl<K: DeriveXOnly> Derive<DerivedScript> for TrMusig<K> {
+    fn default_keychain(&self) -> Keychain { Keychain::OUTER }
+
+    fn keychains(&self) -> BTreeSet<Keychain> { BTreeSet::from([Keychain::OUTER, Keychain::INNER]) }
+
+    fn derive(
+        &self,
+        keychain: impl Into<Keychain>,
+        index: impl Into<NormalIndex>,
+    ) -> DerivedScript {
+        let (output_key, _) = self.aggregate_key(keychain.into(), index.into());
+        DerivedScript::Tr(ScriptPubkey::p2tr(output_key))
+    }
+}
+
+impl<K: DeriveXOnly> Descriptor<K> for TrMusig<K> {
+    type KeyIter<'k> = vec::IntoIter<&'k K> where Self: 'k, K: 'k;
+    type VarIter<'v> = iter::Empty<&'v ()> where Self: 'v, (): 'v;
+    type XpubIter<'x> = vec::IntoIter<&'x XpubSpec> where Self: 'x;
+
+    fn class(&self) -> SpkClass { SpkClass::P2tr }
+
+    fn keys(&self) -> Self::KeyIter<'_> { self.participants.iter().collect::<Vec<_>>().into_iter() }
+
+    fn vars(&self) -> Self::VarIter<'_> { iter::empty() }
+
+    fn xpubs(&self) -> Self::XpubIter<'_> {
+        self.participants.iter().map(K::xpub_spec).collect::<Vec<_>>().into_iter()
+    }
+
+    fn compr_keyset(&self, _terminal: Terminal) -> IndexMap<CompressedPk, KeyOrigin> {
+        IndexMap::new()
+    }
+
+    fn xonly_keyset(&self, terminal: Terminal) -> IndexMap<XOnlyPk, TapDerivation> {
+        // The aggregate key itself has no single key origin to report (it belongs to no one
+        // participant's xpub); `aggregate_pubkey` exposes it separately for `tap_internal_key`.
+        // Each participant is still listed here, keyed by its own derived xonly key, so every
+        // cosigner can recognize which of its keys take part in the aggregate.
+        self.participants
+            .iter()
+            .map(|key| {
+                let xonly = key.derive(terminal.keychain, terminal.index);
+                let derivation = TapDerivation {
+                    leaf_hashes: BTreeSet::new(),
+                    origin: key.xpub_spec().origin(terminal),
+                };
+                (xonly, derivation)
+            })
+            .collect()
+    }
+
+    fn psbt_tap_derivation(
+        &self,
+        terminal: Terminal,
+    ) -> (Option<XOnlyPk>, IndexMap<XOnlyPk, TapDerivation>) {
+        // The default `Descriptor::psbt_tap_derivation` picks the key with empty
+        // `leaf_hashes` as the internal key, which assumes a single key-path key; `TrMusig`
+        // has `n` such keys (one per participant, none of them tied to a script leaf), none
+        // of which is the actual output key. Override with the real MuSig2 aggregate key.
+        (Some(self.aggregate_pubkey(terminal)), self.xonly_keyset(terminal))
+    }
+}
+
+impl<K: DeriveXOnly> TrMusig<K> {
+    /// Computes the MuSig2 aggregate key used as the taproot internal (and, since this
+    /// descriptor is key-path only, output) key at the given `terminal`.
+    pub fn aggregate_pubkey(&self, terminal: Terminal) -> XOnlyPk {
+        self.aggregate_key(terminal.keychain, terminal.index).0
+    }
+}
+
+fn keyagg_list_hash(keys: &[XOnlyPk]) -> [u8; 32] {
+    let mut msg = Vec::with_capacity(keys.len() * 32);
+    for key in keys {
+        msg.extend_from_slice(&key.serialize());
+    }
+    tagged_hash("KeyAgg list", &msg)
+}
+
+fn keyagg_coefficient(list_hash: [u8; 32], key: XOnlyPk) -> Scalar {
+    let mut msg = Vec::with_capacity(64);
+    msg.extend_from_slice(&list_hash);
+    msg.extend_from_slice(&key.serialize());
+    let hash = tagged_hash("KeyAgg coefficient", &msg);
+    Scalar::from_be_bytes(hash).expect("tagged hash is a valid scalar")
+}
+
+fn scalar_one() -> Scalar {
+    let mut bytes = [0u8; 32];
+    bytes[31] = 1;
+    Scalar::from_be_bytes(bytes).expect("1 is a valid scalar")
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::SecretKey;
+
+    use super::*;
+
+    // These exercise the MuSig2 key-aggregation primitives directly against fixed secp256k1
+    // keys; they are self-consistency checks on this implementation, not a replay of the
+    // published BIP327 key-aggregation test vectors. `TrMusig::aggregate_key` sorts the
+    // key list before `KeyAgg list` is hashed (unlike the unsorted BIP327 vectors),
+    // so this module will not reproduce those vectors' expected outputs as-is.
+
+    fn xonly(secret: u8) -> XOnlyPk {
+        let mut bytes = [0u8; 32];
+        bytes[31] = secret;
+        let secret_key = SecretKey::from_slice(&bytes).unwrap();
+        secret_key.x_only_public_key(SECP256K1).0
+    }
+
+    #[test]
+    fn list_hash_is_deterministic() {
+        let keys = [xonly(1), xonly(2), xonly(3)];
+        assert_eq!(keyagg_list_hash(&keys), keyagg_list_hash(&keys));
+    }
+
+    #[test]
+    fn list_hash_depends_on_key_order() {
+        let a = [xonly(1), xonly(2), xonly(3)];
+        let b = [xonly(2), xonly(1), xonly(3)];
+        // BIP327's `KeyAgg list` hash commits to the list in the order given, so permuting
+        // the (already-sorted, in our case) input must change the hash.
+        assert_ne!(keyagg_list_hash(&a), keyagg_list_hash(&b));
+    }
+
+    #[test]
+    fn coefficient_differs_per_key_and_per_list() {
+        let list_a = keyagg_list_hash(&[xonly(1), xonly(2)]);
+        let list_b = keyagg_list_hash(&[xonly(1), xonly(3)]);
+
+        let c1 = keyagg_coefficient(list_a, xonly(1));
+        let c2 = keyagg_coefficient(list_a, xonly(2));
+        assert_ne!(c1, c2, "different keys under the same list must get different coefficients");
+
+        let c1_other_list = keyagg_coefficient(list_b, xonly(1));
+        assert_ne!(c1, c1_other_list, "the same key under a different list must get a different coefficient");
+    }
+
+    #[test]
+    fn scalar_one_is_the_multiplicative_identity() {
+        let one = scalar_one();
+        let key = xonly(5);
+        let tweaked = key
+            .public_key(Parity::Even)
+            .mul_tweak(SECP256K1, &one)
+            .expect("tweak by 1 cannot fail");
+        assert_eq!(tweaked.x_only_public_key().0, key);
+    }
+}
@@ -0,0 +1,265 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2023 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2023 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2023 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bitcoin Core-compatible textual descriptor format: `wpkh(...)`, `tr(...)` etc, each
+//! suffixed with an 8-character descriptor checksum, as used by `getdescriptorinfo` /
+//! `importdescriptors` and by other wallets exchanging descriptors out of band.
+
+use std::fmt;
+use std::str::FromStr;
+
+use derive::DeriveSet;
+
+use crate::{StdDescr, TrKey, Wpkh};
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~\
+     ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GEN: [u64; 5] = [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd];
+
+fn poly_mod(c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7_ffff_ffff) << 5) ^ val;
+    for (i, gen) in GEN.iter().enumerate() {
+        if (c0 >> i) & 1 == 1 {
+            c ^= gen;
+        }
+    }
+    c
+}
+
+/// Computes the 8-character Bech32-style checksum Bitcoin Core appends to textual
+/// descriptors (the `#xxxxxxxx` suffix), as specified by BIP380.
+fn descriptor_checksum(descriptor: &str) -> Option<String> {
+    let mut c = 1u64;
+    let mut cls = 0u64;
+    let mut clscount = 0u64;
+
+    for ch in descriptor.chars() {
+        let pos = INPUT_CHARSET.find(ch)? as u64;
+        c = poly_mod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        clscount += 1;
+        if clscount == 3 {
+            c = poly_mod(c, cls);
+            cls = 0;
+            clscount = 0;
+        }
+    }
+    if clscount > 0 {
+        c = poly_mod(c, cls);
+    }
+    for _ in 0..8 {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+
+    let mut checksum = String::with_capacity(8);
+    for j in 0..8 {
+        let idx = (c >> (5 * (7 - j))) & 31;
+        checksum.push(CHECKSUM_CHARSET[idx as usize] as char);
+    }
+    Some(checksum)
+}
+
+fn write_with_checksum(f: &mut fmt::Formatter, body: &str) -> fmt::Result {
+    let checksum = descriptor_checksum(body).expect("descriptor body uses an invalid character");
+    write!(f, "{body}#{checksum}")
+}
+
+fn split_checksum(s: &str) -> Result<(&str, Option<&str>), DescriptorParseError> {
+    match s.rsplit_once('#') {
+        Some((body, checksum)) => {
+            if checksum.len() != 8 {
+                return Err(DescriptorParseError::InvalidChecksum(checksum.to_owned()));
+            }
+            let expected =
+                descriptor_checksum(body).ok_or_else(|| DescriptorParseError::InvalidChar(body.to_owned()))?;
+            if expected != checksum {
+                return Err(DescriptorParseError::ChecksumMismatch {
+                    expected,
+                    actual: checksum.to_owned(),
+                });
+            }
+            Ok((body, Some(checksum)))
+        }
+        None => Ok((s, None)),
+    }
+}
+
+fn unwrap_fn<'s>(s: &'s str, name: &str) -> Result<&'s str, DescriptorParseError> {
+    let prefix = format!("{name}(");
+    let inner = s
+        .strip_prefix(prefix.as_str())
+        .and_then(|rest| rest.strip_suffix(')'))
+        .ok_or_else(|| DescriptorParseError::InvalidFragment(s.to_owned()))?;
+    Ok(inner)
+}
+
+/// Error parsing a textual descriptor.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DescriptorParseError {
+    /// descriptor checksum '{0}' is not 8 characters long
+    InvalidChecksum(String),
+
+    /// descriptor '{0}' contains a character not allowed in a descriptor
+    InvalidChar(String),
+
+    /// descriptor checksum mismatch: expected '{expected}', found '{actual}'
+    ChecksumMismatch { expected: String, actual: String },
+
+    /// descriptor fragment '{0}' is not recognized or is malformed
+    InvalidFragment(String),
+
+    /// error parsing descriptor key: {0}
+    InvalidKey(String),
+
+    /// descriptor variant has no representation in the Bitcoin Core textual format
+    NotRepresentable,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Expected checksums below are cross-checked against an independent Python
+    // implementation of the BIP380 polymod algorithm, not copied from `poly_mod`/
+    // `descriptor_checksum` above, so they catch a slip in the generator-constant table or
+    // the character-class bucketing here.
+
+    #[test]
+    fn checksum_known_vectors() {
+        assert_eq!(descriptor_checksum("wpkh(KEY)").as_deref(), Some("5etmewd7"));
+        assert_eq!(descriptor_checksum("tr(KEY)").as_deref(), Some("826s68p5"));
+        assert_eq!(descriptor_checksum("a").as_deref(), Some("ywg0ausw"));
+        assert_eq!(descriptor_checksum("").as_deref(), Some("7h0w2xvg"));
+    }
+
+    #[test]
+    fn checksum_rejects_character_outside_input_charset() {
+        // '\n' is not part of `INPUT_CHARSET`.
+        assert_eq!(descriptor_checksum("wpkh(KEY)\n"), None);
+    }
+
+    #[test]
+    fn split_checksum_accepts_matching_checksum() {
+        let (body, checksum) = split_checksum("wpkh(KEY)#5etmewd7").unwrap();
+        assert_eq!(body, "wpkh(KEY)");
+        assert_eq!(checksum, Some("5etmewd7"));
+    }
+
+    #[test]
+    fn split_checksum_rejects_mismatched_checksum() {
+        let err = split_checksum("wpkh(KEY)#00000000").unwrap_err();
+        assert!(matches!(err, DescriptorParseError::ChecksumMismatch { .. }));
+    }
+
+    #[test]
+    fn split_checksum_rejects_short_checksum() {
+        let err = split_checksum("wpkh(KEY)#123").unwrap_err();
+        assert!(matches!(err, DescriptorParseError::InvalidChecksum(_)));
+    }
+
+    #[test]
+    fn split_checksum_passes_through_bare_body() {
+        let (body, checksum) = split_checksum("wpkh(KEY)").unwrap();
+        assert_eq!(body, "wpkh(KEY)");
+        assert_eq!(checksum, None);
+    }
+}
+
+impl<K: fmt::Display> fmt::Display for Wpkh<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_with_checksum(f, &format!("wpkh({})", self.as_key()))
+    }
+}
+
+impl<K: FromStr> FromStr for Wpkh<K> {
+    type Err = DescriptorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (body, _) = split_checksum(s)?;
+        let inner = unwrap_fn(body, "wpkh")?;
+        let key = K::from_str(inner).map_err(|_| DescriptorParseError::InvalidKey(inner.to_owned()))?;
+        Ok(Wpkh::new(key))
+    }
+}
+
+impl<K: fmt::Display> fmt::Display for TrKey<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_with_checksum(f, &format!("tr({})", self.as_key()))
+    }
+}
+
+impl<K: FromStr> FromStr for TrKey<K> {
+    type Err = DescriptorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (body, _) = split_checksum(s)?;
+        let inner = unwrap_fn(body, "tr")?;
+        let key = K::from_str(inner).map_err(|_| DescriptorParseError::InvalidKey(inner.to_owned()))?;
+        Ok(TrKey::new(key))
+    }
+}
+
+impl<S: DeriveSet> StdDescr<S>
+where
+    S::Compr: fmt::Display,
+    S::XOnly: fmt::Display,
+{
+    /// Renders this descriptor in the Bitcoin Core textual format, including its checksum.
+    ///
+    /// Returns `Err(DescriptorParseError::NotRepresentable)` for variants that have no
+    /// textual representation yet (currently `WshSortedMulti`, `TrMusig` and `TrTree`) rather
+    /// than panicking, unlike a `Display` impl would have to since `fmt::Display::fmt` cannot
+    /// report that a value has no valid rendering.
+    pub fn to_descriptor_string(&self) -> Result<String, DescriptorParseError> {
+        match self {
+            StdDescr::Wpkh(d) => Ok(d.to_string()),
+            StdDescr::WshSortedMulti(_) | StdDescr::TrMusig(_) | StdDescr::TrTree(_) => {
+                Err(DescriptorParseError::NotRepresentable)
+            }
+            StdDescr::TrKey(d) => Ok(d.to_string()),
+        }
+    }
+}
+
+impl<S: DeriveSet> FromStr for StdDescr<S>
+where
+    S::Compr: FromStr,
+    S::XOnly: FromStr,
+{
+    type Err = DescriptorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (body, _) = split_checksum(s)?;
+        if body.starts_with("wpkh(") {
+            return Wpkh::<S::Compr>::from_str(s).map(StdDescr::Wpkh);
+        }
+        if body.starts_with("tr(") {
+            return TrKey::<S::XOnly>::from_str(s).map(StdDescr::TrKey);
+        }
+        Err(DescriptorParseError::InvalidFragment(body.to_owned()))
+    }
+}
@@ -0,0 +1,56 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2023 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2023 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2023 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! BIP340 tagged hashing, shared by the taproot script-tree (`tr.rs`) and MuSig2 (`musig.rs`)
+//! descriptors so both build their domain-separated hashes (`TapLeaf`, `TapBranch`,
+//! `TapTweak`, `KeyAgg list`, `KeyAgg coefficient`, ...) off a single implementation.
+
+use sha2::{Digest, Sha256};
+
+/// Computes `SHA256(SHA256(tag) || SHA256(tag) || msg)`, as specified by BIP340.
+pub(crate) fn tagged_hash(tag: &str, msg: &[u8]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut engine = Sha256::new();
+    engine.update(tag_hash);
+    engine.update(tag_hash);
+    engine.update(msg);
+    engine.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tap_leaf_of_empty_message_is_deterministic() {
+        let a = tagged_hash("TapLeaf", &[]);
+        let b = tagged_hash("TapLeaf", &[]);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn different_tags_diverge() {
+        let a = tagged_hash("TapLeaf", b"hello");
+        let b = tagged_hash("TapBranch", b"hello");
+        assert_ne!(a, b);
+    }
+}